@@ -12,7 +12,7 @@
 
 extern crate backtrace_sys as bt;
 
-use core::{ptr, slice};
+use core::{mem, ptr, slice};
 
 use libc::{self, c_char, c_int, c_void, uintptr_t};
 
@@ -31,6 +31,7 @@ pub enum Symbol {
         filename: *const c_char,
         lineno: c_int,
         function: *const c_char,
+        inline_depth: u32,
     },
 }
 
@@ -97,25 +98,110 @@ impl Symbol {
             Symbol::Pcinfo { lineno, .. } => Some(lineno as u32),
         }
     }
+
+    /// How deep into a chain of inlined calls this frame is, with `0` being
+    /// the innermost frame libbacktrace reported for the resolved address.
+    ///
+    /// libbacktrace invokes its pcinfo callback once per frame in the inline
+    /// chain for a single address, innermost first, so this is just that
+    /// callback's invocation count for the current `resolve`.
+    pub fn inline_depth(&self) -> u32 {
+        match *self {
+            Symbol::Syminfo { .. } => 0,
+            Symbol::Pcinfo { inline_depth, .. } => inline_depth,
+        }
+    }
+
+    /// Whether this frame came from an inlined call, i.e. it wasn't the
+    /// first frame reported for this address.
+    pub fn is_inlined(&self) -> bool {
+        self.inline_depth() > 0
+    }
+}
+
+/// A best-effort description of an error libbacktrace reported while trying
+/// to resolve a symbol.
+///
+/// libbacktrace hands us a transient `msg` pointer that may not outlive the
+/// callback, so the message is copied into a small inline buffer instead of
+/// being stored as a pointer. Longer messages are truncated.
+pub struct ResolveError {
+    buf: [u8; 64],
+    len: usize,
+    errnum: c_int,
+}
+
+impl ResolveError {
+    fn new(msg: *const c_char, errnum: c_int) -> ResolveError {
+        let mut ret = ResolveError {
+            buf: [0; 64],
+            len: 0,
+            errnum: errnum,
+        };
+        if !msg.is_null() {
+            unsafe {
+                let len = libc::strlen(msg).min(ret.buf.len());
+                ptr::copy_nonoverlapping(msg as *const u8, ret.buf.as_mut_ptr(), len);
+                ret.len = len;
+            }
+        }
+        ret
+    }
+
+    /// Returns the raw message reported by libbacktrace, if any, possibly
+    /// truncated to fit in a small inline buffer.
+    pub fn message(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Returns the `errnum` reported alongside the message, as passed to
+    /// libbacktrace's `error_callback`. A negative value typically means
+    /// "no error information" rather than a real errno.
+    pub fn errno(&self) -> i32 {
+        self.errnum as i32
+    }
 }
 
-extern "C" fn error_cb(_data: *mut c_void, _msg: *const c_char, _errnum: c_int) {
-    // do nothing for now
+// Context threaded through the `data` pointer of `backtrace_pcinfo` and
+// `backtrace_syminfo` so that `error_cb` has somewhere to stash what went
+// wrong alongside the symbol callback the caller actually wants to run.
+struct ResolveContext<'a> {
+    cb: &'a mut FnMut(&super::Symbol),
+    error: Option<ResolveError>,
+    // The original, absolute address `resolve` was asked about. libbacktrace
+    // only ever echoes back whatever address we look it up with, which since
+    // we now look modules up by their file-relative address is no longer the
+    // address the caller gave us -- so `*_cb` below reports this instead.
+    addr: uintptr_t,
+    // How many times `pcinfo_cb` has fired so far for this `resolve` call,
+    // used to report each frame's depth in the inline chain.
+    pcinfo_calls: u32,
+}
+
+extern "C" fn error_cb(data: *mut c_void, msg: *const c_char, errnum: c_int) {
+    if data.is_null() {
+        return;
+    }
+    unsafe {
+        let cx = &mut *(data as *mut ResolveContext);
+        cx.error = Some(ResolveError::new(msg, errnum));
+    }
 }
 
 extern "C" fn syminfo_cb(
     data: *mut c_void,
-    pc: uintptr_t,
+    _pc: uintptr_t,
     symname: *const c_char,
     _symval: uintptr_t,
     _symsize: uintptr_t,
 ) {
     unsafe {
+        let addr = (*(data as *mut ResolveContext)).addr;
         call(
             data,
             &super::Symbol {
                 inner: Symbol::Syminfo {
-                    pc: pc,
+                    pc: addr,
                     symname: symname,
                 },
             },
@@ -125,7 +211,7 @@ extern "C" fn syminfo_cb(
 
 extern "C" fn pcinfo_cb(
     data: *mut c_void,
-    pc: uintptr_t,
+    _pc: uintptr_t,
     filename: *const c_char,
     lineno: c_int,
     function: *const c_char,
@@ -134,14 +220,19 @@ extern "C" fn pcinfo_cb(
         if filename.is_null() || function.is_null() {
             return -1;
         }
+        let cx = &mut *(data as *mut ResolveContext);
+        let addr = cx.addr;
+        let inline_depth = cx.pcinfo_calls;
+        cx.pcinfo_calls += 1;
         call(
             data,
             &super::Symbol {
                 inner: Symbol::Pcinfo {
-                    pc: pc,
+                    pc: addr,
                     filename: filename,
                     lineno: lineno,
                     function: function,
+                    inline_depth: inline_depth,
                 },
             },
         );
@@ -150,12 +241,186 @@ extern "C" fn pcinfo_cb(
 }
 
 unsafe fn call(data: *mut c_void, sym: &super::Symbol) {
-    let cb = data as *mut &mut FnMut(&super::Symbol);
+    let cx = &mut *(data as *mut ResolveContext);
     let mut bomb = ::Bomb { enabled: true };
-    (*cb)(sym);
+    (cx.cb)(sym);
     bomb.enabled = false;
 }
 
+// Whether `backtrace_state` is created with libbacktrace's threadsafe flag
+// set, and the shared module table is guarded by a real lock below, instead
+// of relying entirely on the caller to externally synchronize `resolve`.
+cfg_if! {
+    if #[cfg(feature = "libbacktrace-threadsafe")] {
+        const THREADSAFE: c_int = 1;
+
+        use std::sync::{Mutex, MutexGuard, Once};
+
+        // Guards `MODULES`/`NMODULES` so multiple threads can symbolicate
+        // concurrently (e.g. a profiler dumping every thread's stack at
+        // once) instead of all funneling through one external lock.
+        struct TableGuard(MutexGuard<'static, ()>);
+
+        fn lock_table() -> TableGuard {
+            static INIT: Once = Once::new();
+            static mut LOCK: *const Mutex<()> = ptr::null();
+            unsafe {
+                INIT.call_once(|| {
+                    LOCK = Box::into_raw(Box::new(Mutex::new(())));
+                });
+                TableGuard((*LOCK).lock().unwrap_or_else(|e| e.into_inner()))
+            }
+        }
+    } else {
+        // Don't exercise threadsafe capabilities of libbacktrace by default
+        // since `resolve` is documented as externally synchronized, and a
+        // real lock would be dead weight for `no_std`/minimal builds.
+        const THREADSAFE: c_int = 0;
+
+        struct TableGuard;
+
+        fn lock_table() -> TableGuard {
+            TableGuard
+        }
+    }
+}
+
+const MAX_MODULES: usize = 64;
+const MAX_MODULE_PATH: usize = 256;
+
+#[derive(Copy, Clone)]
+struct ModuleState {
+    path: [u8; MAX_MODULE_PATH],
+    path_len: usize,
+    base: usize,
+    state: *mut bt::backtrace_state,
+}
+
+const EMPTY_MODULE: ModuleState = ModuleState {
+    path: [0; MAX_MODULE_PATH],
+    path_len: 0,
+    base: 0,
+    state: ptr::null_mut(),
+};
+
+// An append-only table mapping a module's on-disk path to the
+// `backtrace_state` created for it, plus the load bias libbacktrace needs to
+// turn a runtime address into a file-relative one. Entries are never removed
+// or mutated once filled in, mirroring `backtrace_state`'s own forever-lived
+// design (see `init_state` below). Access is serialized by `lock_table`,
+// which with the `libbacktrace-threadsafe` feature off is just the caller's
+// existing obligation to externally synchronize calls to `resolve`.
+static mut MODULES: [ModuleState; MAX_MODULES] = [EMPTY_MODULE; MAX_MODULES];
+static mut NMODULES: usize = 0;
+
+// Finds the shared object (or main executable) that `addr` falls inside of,
+// returning its on-disk path and the base address it was loaded at.
+cfg_if! {
+    if #[cfg(unix)] {
+        unsafe fn module_for_addr(addr: usize) -> Option<(*const c_char, usize)> {
+            let mut info: libc::Dl_info = mem::zeroed();
+            if libc::dladdr(addr as *const c_void, &mut info) == 0 || info.dli_fname.is_null() {
+                None
+            } else {
+                Some((info.dli_fname, info.dli_fbase as usize))
+            }
+        }
+
+        // The base address of the main executable, as opposed to any shared
+        // library loaded alongside it.
+        //
+        // `dladdr`'s `dli_fname` for the main executable is whatever path it
+        // happened to be exec'd with (e.g. a relative `./foo`), which goes
+        // stale the moment the process `chdir`s and was never reliable to
+        // begin with -- unlike `dli_fbase`, which is just where the loader
+        // put it. We take an address we know is in the main executable (this
+        // function's own address, since this crate is always statically
+        // linked in) and remember its base so `state_for_addr` can route
+        // addresses there to the existing `init_state`/`load_filename` path
+        // instead of creating a `backtrace_state` from that unreliable path.
+        unsafe fn main_module_base() -> Option<usize> {
+            static mut BASE: usize = !0;
+
+            // Guarded by the same lock as `MODULES`/`NMODULES`: under
+            // `libbacktrace-threadsafe` two threads can both race to
+            // initialize `BASE` on their first call into `resolve`.
+            let _guard = lock_table();
+
+            if BASE != !0 {
+                return Some(BASE);
+            }
+
+            let mut info: libc::Dl_info = mem::zeroed();
+            let addr = main_module_base as usize;
+            if libc::dladdr(addr as *const c_void, &mut info) == 0 {
+                return None;
+            }
+            BASE = info.dli_fbase as usize;
+            Some(BASE)
+        }
+    } else {
+        unsafe fn module_for_addr(_addr: usize) -> Option<(*const c_char, usize)> {
+            None
+        }
+
+        unsafe fn main_module_base() -> Option<usize> {
+            None
+        }
+    }
+}
+
+// Looks up the cached `backtrace_state` for the module at `path`, creating
+// and caching one (biased by `base`) if this is the first address we've seen
+// land in it. Returns null if `path` is too long to store, the table is
+// full, or state creation failed; `state_for_addr` falls back to the single
+// main-executable state in that case, and `resolve_in` reports an error.
+unsafe fn state_for_module(path: &[u8], base: usize) -> (*mut bt::backtrace_state, usize) {
+    let _guard = lock_table();
+
+    // Leave room for the NUL terminator `backtrace_create_state` expects;
+    // `buf` is zero-initialized so `buf[len]` already serves as one.
+    //
+    // A path that doesn't fit is a hard failure rather than something we
+    // truncate and key the cache on: two distinct modules can easily share
+    // an identical prefix longer than `MAX_MODULE_PATH` (deeply nested
+    // cargo-registry/CI build directories, for instance), and aliasing them
+    // onto the same cached `backtrace_state` would silently symbolicate one
+    // module's addresses against another's debug info.
+    if path.len() > MAX_MODULE_PATH - 1 {
+        return (ptr::null_mut(), 0);
+    }
+    let len = path.len();
+
+    for module in MODULES[..NMODULES].iter() {
+        if &module.path[..module.path_len] == path {
+            return (module.state, module.base);
+        }
+    }
+
+    if NMODULES >= MAX_MODULES {
+        return (ptr::null_mut(), 0);
+    }
+
+    let mut buf = [0; MAX_MODULE_PATH];
+    buf[..len].copy_from_slice(path);
+
+    let state = bt::backtrace_create_state(
+        buf.as_ptr() as *const c_char,
+        THREADSAFE,
+        error_cb,
+        ptr::null_mut(), // no extra data
+    );
+
+    MODULES[NMODULES] = ModuleState {
+        path: buf,
+        path_len: len,
+        base: base,
+        state: state,
+    };
+    NMODULES += 1;
+    (state, base)
+}
+
 // The libbacktrace API supports creating a state, but it does not
 // support destroying a state. I personally take this to mean that a
 // state is meant to be created and then live forever.
@@ -167,20 +432,26 @@ unsafe fn call(data: *mut c_void, sym: &super::Symbol) {
 // that is calculated the first time this is requested. Remember that
 // backtracing all happens serially (one global lock).
 //
-// Note the lack of synchronization here is due to the requirement that
-// `resolve` is externally synchronized.
+// With the `libbacktrace-threadsafe` feature off, the lack of synchronization
+// here is fine because `resolve` is documented as externally synchronized;
+// with it on, `lock_table` below serializes this alongside the per-module
+// table.
+//
+// This is the state used for the main executable, and the fallback when an
+// address can't be mapped to one of the per-module states above (e.g. on
+// platforms where `module_for_addr` can't find anything).
 unsafe fn init_state() -> *mut bt::backtrace_state {
     static mut STATE: *mut bt::backtrace_state = 0 as *mut _;
 
+    let _guard = lock_table();
+
     if !STATE.is_null() {
         return STATE;
     }
 
     STATE = bt::backtrace_create_state(
         load_filename(),
-        // Don't exercise threadsafe capabilities of libbacktrace since
-        // we're always calling it in a synchronized fashion.
-        0,
+        THREADSAFE,
         error_cb,
         ptr::null_mut(), // no extra data
     );
@@ -244,29 +515,414 @@ unsafe fn init_state() -> *mut bt::backtrace_state {
     }
 }
 
-pub unsafe fn resolve(what: ResolveWhat, mut cb: &mut FnMut(&super::Symbol)) {
-    let symaddr = what.address_or_ip();
+// Maps `addr` to the module it falls inside of and returns that module's
+// `backtrace_state` (lazily created) along with the file-relative address to
+// look it up with, falling back to `init_state`'s main-executable state (and
+// the address unchanged) when `addr` can't be mapped to a module, or when it
+// maps to the main executable itself (see `main_module_base`).
+unsafe fn state_for_addr(addr: usize) -> (*mut bt::backtrace_state, usize) {
+    match module_for_addr(addr) {
+        Some((_, base)) if Some(base) == main_module_base() => (init_state(), addr),
+        Some((path, base)) => {
+            let path = slice::from_raw_parts(path as *const u8, libc::strlen(path));
+            // Bias by the base `dladdr` just reported rather than the cached
+            // one, which is 0 for an entry `resolve_in` created for this path.
+            let (module_state, _) = state_for_module(path, base);
+            if module_state.is_null() {
+                (init_state(), addr)
+            } else {
+                (module_state, addr.wrapping_sub(base))
+            }
+        }
+        None => (init_state(), addr),
+    }
+}
+
+/// Resolves `what` to zero or more symbols, invoking `cb` for each one found,
+/// and reports why nothing was found (or why lookup was incomplete) via the
+/// returned `ResolveError`, on a best-effort basis.
+///
+/// See `resolve` for a version of this that doesn't require handling the
+/// error case.
+pub unsafe fn try_resolve(
+    what: ResolveWhat,
+    cb: &mut FnMut(&super::Symbol),
+) -> Option<ResolveError> {
+    let symaddr = what.address_or_ip() as usize;
 
-    // backtrace errors are currently swept under the rug
-    let state = init_state();
+    // Map the address to the module (main executable or shared library) it
+    // falls inside of, so we symbolicate against that module's own state
+    // rather than always assuming the main executable.
+    let (state, file_relative_addr) = state_for_addr(symaddr);
     if state.is_null() {
-        return;
+        return Some(ResolveError::new(
+            b"failed to create backtrace state\0".as_ptr() as *const c_char,
+            -1,
+        ));
     }
 
+    let mut cx = ResolveContext {
+        cb: cb,
+        error: None,
+        addr: symaddr as uintptr_t,
+        pcinfo_calls: 0,
+    };
+    let cx_ptr = &mut cx as *mut ResolveContext as *mut c_void;
+
     let ret = bt::backtrace_pcinfo(
         state,
-        symaddr as uintptr_t,
+        file_relative_addr as uintptr_t,
         pcinfo_cb,
         error_cb,
-        &mut cb as *mut _ as *mut _,
+        cx_ptr,
     );
     if ret != 0 {
         bt::backtrace_syminfo(
             state,
-            symaddr as uintptr_t,
+            file_relative_addr as uintptr_t,
             syminfo_cb,
             error_cb,
-            &mut cb as *mut _ as *mut _,
+            cx_ptr,
         );
     }
+
+    cx.error
+}
+
+pub unsafe fn resolve(what: ResolveWhat, cb: &mut FnMut(&super::Symbol)) {
+    drop(try_resolve(what, cb));
+}
+
+/// Resolves `addr` against the `backtrace_state` for `path`, rather than
+/// whichever module the running process happens to have loaded at that
+/// address.
+///
+/// This is the entry point for symbolicating addresses that didn't come from
+/// this process at all -- e.g. a core dump analyzer working against the
+/// crashed process's executable, or a profiler resolving addresses against a
+/// stripped binary's companion `.debug` file. `addr` should already be
+/// file-relative (the bias, if any, is the caller's to account for).
+///
+/// Like `init_state`, the state created for `path` is cached forever and
+/// reused on subsequent calls with the same path.
+#[cfg(feature = "std")]
+pub unsafe fn resolve_in(
+    path: &::std::path::Path,
+    addr: usize,
+    cb: &mut FnMut(&super::Symbol),
+) -> Option<ResolveError> {
+    use std::os::unix::prelude::*;
+
+    let (state, _base) = state_for_module(path.as_os_str().as_bytes(), 0);
+    if state.is_null() {
+        return Some(ResolveError::new(
+            b"failed to create backtrace state for path\0".as_ptr() as *const c_char,
+            -1,
+        ));
+    }
+
+    let mut cx = ResolveContext {
+        cb: cb,
+        error: None,
+        addr: addr as uintptr_t,
+        pcinfo_calls: 0,
+    };
+    let cx_ptr = &mut cx as *mut ResolveContext as *mut c_void;
+
+    let ret = bt::backtrace_pcinfo(state, addr as uintptr_t, pcinfo_cb, error_cb, cx_ptr);
+    if ret != 0 {
+        bt::backtrace_syminfo(state, addr as uintptr_t, syminfo_cb, error_cb, cx_ptr);
+    }
+
+    cx.error
+}
+
+/// Maximum number of inlined frames `resolve_fileline` will report for a
+/// single address; deeper chains are silently truncated.
+const MAX_FILELINE_FRAMES: usize = 32;
+
+/// One `filename:line` pair for a single frame in an address's inline
+/// expansion, as collected by `resolve_fileline`.
+#[derive(Copy, Clone)]
+pub struct FileLine {
+    filename: *const c_char,
+    lineno: c_int,
+}
+
+impl FileLine {
+    pub fn filename_raw(&self) -> Option<BytesOrWideString> {
+        if self.filename.is_null() {
+            None
+        } else {
+            unsafe {
+                let len = libc::strlen(self.filename);
+                Some(BytesOrWideString::Bytes(slice::from_raw_parts(
+                    self.filename as *const u8,
+                    len,
+                )))
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn filename(&self) -> Option<&::std::path::Path> {
+        use std::ffi::OsStr;
+        use std::os::unix::prelude::*;
+        use std::path::Path;
+
+        match self.filename_raw() {
+            Some(BytesOrWideString::Bytes(bytes)) => Some(Path::new(OsStr::from_bytes(bytes))),
+            _ => None,
+        }
+    }
+
+    pub fn lineno(&self) -> Option<u32> {
+        if self.lineno <= 0 {
+            None
+        } else {
+            Some(self.lineno as u32)
+        }
+    }
+}
+
+/// A stack-allocated, fixed-capacity buffer of `FileLine`s, innermost inlined
+/// frame first, as filled in by `resolve_fileline`.
+pub struct FileLines {
+    buf: [FileLine; MAX_FILELINE_FRAMES],
+    len: usize,
+}
+
+impl ::core::ops::Deref for FileLines {
+    type Target = [FileLine];
+
+    fn deref(&self) -> &[FileLine] {
+        &self.buf[..self.len]
+    }
+}
+
+// `resolve_fileline` doesn't report errors (there's nowhere in `FileLines` to
+// put one), so unlike `error_cb` this just drops whatever libbacktrace says.
+extern "C" fn fileline_error_cb(_data: *mut c_void, _msg: *const c_char, _errnum: c_int) {}
+
+extern "C" fn fileline_pcinfo_cb(
+    data: *mut c_void,
+    _pc: uintptr_t,
+    filename: *const c_char,
+    lineno: c_int,
+    _function: *const c_char,
+) -> c_int {
+    unsafe {
+        let lines = &mut *(data as *mut FileLines);
+        if lines.len < MAX_FILELINE_FRAMES {
+            lines.buf[lines.len] = FileLine {
+                filename: filename,
+                lineno: lineno,
+            };
+            lines.len += 1;
+        }
+        0
+    }
+}
+
+extern "C" fn fileline_syminfo_cb(
+    data: *mut c_void,
+    _pc: uintptr_t,
+    _symname: *const c_char,
+    _symval: uintptr_t,
+    _symsize: uintptr_t,
+) {
+    unsafe {
+        let lines = &mut *(data as *mut FileLines);
+        if lines.len == 0 {
+            lines.buf[0] = FileLine {
+                filename: ptr::null(),
+                lineno: 0,
+            };
+            lines.len = 1;
+        }
+    }
+}
+
+/// Allocation-free variant of `resolve` that collects the full inline
+/// expansion for `addr` -- one `FileLine` per inlined frame, innermost
+/// first -- into a buffer stack-allocated right here, rather than requiring
+/// callers to drive a closure and accumulate frames themselves.
+///
+/// Falls back to `backtrace_syminfo` (yielding a single frame with no line
+/// number) when `backtrace_pcinfo` finds nothing for `addr` at all. Frames
+/// beyond `MAX_FILELINE_FRAMES` are silently dropped, matching libbacktrace's
+/// own best-effort contract.
+pub unsafe fn resolve_fileline(addr: usize) -> FileLines {
+    let mut lines = FileLines {
+        buf: [FileLine {
+            filename: ptr::null(),
+            lineno: 0,
+        }; MAX_FILELINE_FRAMES],
+        len: 0,
+    };
+
+    let (state, file_relative_addr) = state_for_addr(addr);
+    if state.is_null() {
+        return lines;
+    }
+
+    let lines_ptr = &mut lines as *mut FileLines as *mut c_void;
+    let ret = bt::backtrace_pcinfo(
+        state,
+        file_relative_addr as uintptr_t,
+        fileline_pcinfo_cb,
+        fileline_error_cb,
+        lines_ptr,
+    );
+    if ret != 0 || lines.len == 0 {
+        bt::backtrace_syminfo(
+            state,
+            file_relative_addr as uintptr_t,
+            fileline_syminfo_cb,
+            fileline_error_cb,
+            lines_ptr,
+        );
+    }
+
+    lines
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_error_copies_and_truncates_message() {
+        let err = ResolveError::new(b"no debuginfo present\0".as_ptr() as *const c_char, 42);
+        assert_eq!(err.message(), b"no debuginfo present");
+        assert_eq!(err.errno(), 42);
+
+        let long_msg: Vec<u8> = ::std::iter::repeat(b'x')
+            .take(200)
+            .chain(Some(0))
+            .collect();
+        let err = ResolveError::new(long_msg.as_ptr() as *const c_char, -1);
+        assert_eq!(err.message().len(), 64);
+        assert!(err.message().iter().all(|&b| b == b'x'));
+    }
+
+    #[test]
+    fn resolve_error_handles_null_message() {
+        let err = ResolveError::new(ptr::null(), -1);
+        assert_eq!(err.message(), &[][..]);
+        assert_eq!(err.errno(), -1);
+    }
+
+    #[test]
+    fn pcinfo_inline_depth_is_exposed() {
+        let innermost = Symbol::Pcinfo {
+            pc: 0x1000,
+            filename: ptr::null(),
+            lineno: 0,
+            function: ptr::null(),
+            inline_depth: 0,
+        };
+        assert_eq!(innermost.inline_depth(), 0);
+        assert!(!innermost.is_inlined());
+
+        let inlined = Symbol::Pcinfo {
+            pc: 0x1000,
+            filename: ptr::null(),
+            lineno: 0,
+            function: ptr::null(),
+            inline_depth: 2,
+        };
+        assert_eq!(inlined.inline_depth(), 2);
+        assert!(inlined.is_inlined());
+    }
+
+    #[test]
+    fn syminfo_is_never_inlined() {
+        let sym = Symbol::Syminfo {
+            pc: 0x1000,
+            symname: ptr::null(),
+        };
+        assert_eq!(sym.inline_depth(), 0);
+        assert!(!sym.is_inlined());
+    }
+
+    #[test]
+    fn threadsafe_flag_matches_feature() {
+        if cfg!(feature = "libbacktrace-threadsafe") {
+            assert_eq!(THREADSAFE, 1);
+        } else {
+            assert_eq!(THREADSAFE, 0);
+        }
+    }
+
+    #[test]
+    fn table_lock_can_be_reacquired_across_sequential_calls() {
+        // `main_module_base`, `state_for_module` and `init_state` each take
+        // `lock_table()` in turn during one `resolve`: acquiring and
+        // releasing the lock repeatedly must not deadlock, with or without
+        // `libbacktrace-threadsafe` enabled.
+        for _ in 0..3 {
+            let _guard = lock_table();
+        }
+    }
+
+    fn empty_file_lines() -> FileLines {
+        FileLines {
+            buf: [FileLine {
+                filename: ptr::null(),
+                lineno: 0,
+            }; MAX_FILELINE_FRAMES],
+            len: 0,
+        }
+    }
+
+    #[test]
+    fn fileline_pcinfo_cb_collects_frames_innermost_first() {
+        let mut lines = empty_file_lines();
+        let lines_ptr = &mut lines as *mut FileLines as *mut c_void;
+
+        let name_a = b"a.rs\0";
+        let name_b = b"b.rs\0";
+        fileline_pcinfo_cb(lines_ptr, 0, name_a.as_ptr() as *const c_char, 1, ptr::null());
+        fileline_pcinfo_cb(lines_ptr, 0, name_b.as_ptr() as *const c_char, 2, ptr::null());
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].lineno(), Some(1));
+        assert_eq!(lines[1].lineno(), Some(2));
+    }
+
+    #[test]
+    fn fileline_pcinfo_cb_truncates_past_capacity() {
+        let mut lines = empty_file_lines();
+        let lines_ptr = &mut lines as *mut FileLines as *mut c_void;
+        let name = b"f.rs\0";
+
+        for i in 0..MAX_FILELINE_FRAMES + 5 {
+            fileline_pcinfo_cb(
+                lines_ptr,
+                0,
+                name.as_ptr() as *const c_char,
+                i as c_int,
+                ptr::null(),
+            );
+        }
+
+        assert_eq!(lines.len(), MAX_FILELINE_FRAMES);
+    }
+
+    #[test]
+    fn fileline_syminfo_cb_only_fills_in_when_pcinfo_found_nothing() {
+        let mut lines = empty_file_lines();
+        let lines_ptr = &mut lines as *mut FileLines as *mut c_void;
+
+        fileline_syminfo_cb(lines_ptr, 0, ptr::null(), 0, 0);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].filename_raw(), None);
+        assert_eq!(lines[0].lineno(), None);
+
+        // A second call (e.g. if a caller drove both paths) must not grow
+        // past the one fallback frame.
+        fileline_syminfo_cb(lines_ptr, 0, ptr::null(), 0, 0);
+        assert_eq!(lines.len(), 1);
+    }
 }